@@ -0,0 +1,101 @@
+use uom::si::{
+    f64::ElectricPotential,
+    electric_potential::volt,
+};
+
+/// Fixed-point scale applied before accumulating in `i64`, so the
+/// integrator stages do not lose the fractional volts carried by the
+/// MCP3008 reads.
+const FIXED_POINT_SCALE: f64 = 1_000_000.0;
+
+#[derive(Debug)]
+pub struct Params {
+    pub order: usize,
+    pub decimation: usize,
+}
+
+/// A CIC (cascaded integrator-comb) decimation filter: `order` integrators
+/// run at the full sample rate, every `decimation` samples a comb cascade
+/// of the same order is applied to the decimated output, and the result
+/// is normalized by the filter's fixed gain of `decimation ^ order`.
+pub struct Filter {
+    decimation: usize,
+    gain: f64,
+    integrators: Vec<i64>,
+    comb_delay: Vec<i64>,
+    tick: usize,
+    warmup_remaining: usize,
+}
+
+impl Filter {
+    pub fn new(params: &Params) -> Filter {
+        Filter {
+            decimation: params.decimation,
+            gain: (params.decimation as f64).powi(params.order as i32),
+            integrators: vec![0; params.order],
+            comb_delay: vec![0; params.order],
+            tick: 0,
+            warmup_remaining: params.order,
+        }
+    }
+
+    /// Feeds one full-rate sample into the integrator stages. Returns
+    /// `Some(ElectricPotential)` only on a decimation tick, once the comb
+    /// cascade has been applied and the pipeline has finished filling.
+    pub fn add_sample(&mut self, voltage: ElectricPotential) -> Option<ElectricPotential> {
+        let mut accumulated = (voltage.get::<volt>() * FIXED_POINT_SCALE) as i64;
+        for integrator in self.integrators.iter_mut() {
+            // wrapping, not saturating: the comb stage relies on modular
+            // arithmetic to recover the true difference across an
+            // integrator overflow, which a clamped add would corrupt
+            // permanently from the moment a register saturates
+            accumulated = integrator.wrapping_add(accumulated);
+            *integrator = accumulated;
+        }
+
+        self.tick += 1;
+        if self.tick < self.decimation {
+            return None;
+        }
+        self.tick = 0;
+
+        let mut combed = accumulated;
+        for delay in self.comb_delay.iter_mut() {
+            let previous = *delay;
+            *delay = combed;
+            combed = combed.wrapping_sub(previous);
+        }
+
+        if self.warmup_remaining > 0 {
+            self.warmup_remaining -= 1;
+            return None;
+        }
+
+        Some(ElectricPotential::new::<volt>(combed as f64 / self.gain / FIXED_POINT_SCALE))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passes_a_steady_dc_input_through_once_filled() {
+        let mut filter = Filter::new(&Params { order: 1, decimation: 4, });
+        let mut last = None;
+        for _ in 0..16 {
+            last = filter.add_sample(ElectricPotential::new::<volt>(2.5)).or(last);
+        }
+        let output = last.expect("filter should have settled by now");
+        assert!((output.get::<volt>() - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn discards_samples_during_warmup_and_decimation() {
+        let mut filter = Filter::new(&Params { order: 2, decimation: 3, });
+        // fewer than decimation * (order + 1) samples: nothing should emit yet
+        for _ in 0..5 {
+            assert!(filter.add_sample(ElectricPotential::new::<volt>(1.0)).is_none());
+        }
+    }
+}
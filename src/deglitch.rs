@@ -0,0 +1,119 @@
+use std::{
+    collections::VecDeque,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use uom::si::{
+    f64::{
+        Frequency,
+        ElectricPotential,
+    },
+    frequency::hertz,
+    electric_potential::volt,
+};
+
+/// Stabilizes zero-crossing period estimation against spurious edges: in
+/// place of accepting the first crossing as the period boundary, every
+/// candidate crossing inside a sliding window of one expected period is
+/// kept, and their median is emitted as the true edge time. The period
+/// is then the gap between consecutive median edges.
+pub struct Deglitcher {
+    window: Duration,
+    start: Option<Instant>,
+    crossings: VecDeque<Duration>,
+    last_median_edge: Option<Duration>,
+    last_sign: Option<bool>,
+}
+
+impl Deglitcher {
+    pub fn new(expected_period: Duration) -> Deglitcher {
+        Deglitcher {
+            window: expected_period,
+            start: None,
+            crossings: VecDeque::new(),
+            last_median_edge: None,
+            last_sign: None,
+        }
+    }
+
+    /// Feeds one voltage sample, detecting a zero crossing by sign
+    /// change. Returns a stabilized frequency estimate whenever a new
+    /// median edge advances past the previous one.
+    pub fn add_sample(&mut self, now: Instant, voltage: ElectricPotential) -> Option<Frequency> {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+
+        let sign = voltage.get::<volt>() >= 0.0;
+        let mut result = None;
+
+        if let Some(last_sign) = self.last_sign {
+            // only count negative-to-positive transitions: counting both
+            // edges would put consecutive median edges half a period
+            // apart, reporting double the true frequency
+            if !last_sign && sign {
+                self.crossings.push_back(elapsed);
+                while let Some(&front) = self.crossings.front() {
+                    if elapsed.saturating_sub(front) > self.window {
+                        self.crossings.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+
+                let median_edge = Self::median(&self.crossings);
+                if let Some(previous_edge) = self.last_median_edge {
+                    if median_edge > previous_edge {
+                        let period_secs = (median_edge - previous_edge).as_secs_f64();
+                        if period_secs > 0.0 {
+                            result = Some(Frequency::new::<hertz>(1.0 / period_secs));
+                        }
+                    }
+                }
+                self.last_median_edge = Some(median_edge);
+            }
+        }
+        self.last_sign = Some(sign);
+
+        result
+    }
+
+    fn median(crossings: &VecDeque<Duration>) -> Duration {
+        let mut sorted: Vec<Duration> = crossings.iter().copied().collect();
+        sorted.sort();
+        sorted[sorted.len() / 2]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn reports_the_full_period_of_a_clean_sine() {
+        let true_hz = 50.0;
+        let dt = Duration::from_micros(100);
+        let mut deglitcher = Deglitcher::new(Duration::from_secs_f64(1.0 / true_hz));
+
+        let base = Instant::now();
+        let mut last_result = None;
+        for tick in 0..20_000 {
+            let elapsed = dt * tick;
+            let phase = true_hz * elapsed.as_secs_f64();
+            let voltage = ElectricPotential::new::<volt>((2.0 * PI * phase).sin());
+            if let Some(hz) = deglitcher.add_sample(base + elapsed, voltage) {
+                last_result = Some(hz);
+            }
+        }
+
+        let tracked = last_result.expect("should have reported a frequency by now");
+        assert!(
+            (tracked.get::<hertz>() - true_hz).abs() < 0.5,
+            "expected close to {} Hz, got {} Hz", true_hz, tracked.get::<hertz>(),
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    f64::consts::PI,
+    time::Duration,
+};
+
+use uom::si::{
+    f64::{
+        Frequency,
+        ElectricPotential,
+    },
+    frequency::hertz,
+    electric_potential::volt,
+};
+
+/// Synthesizes a sine carrier by stepping a phase accumulator each loop
+/// iteration, with the amplitude adjustable at runtime so a closed loop
+/// can level the generated signal against a measured target.
+pub struct Generator {
+    phase: f64,
+    frequency: Frequency,
+    amplitude: ElectricPotential,
+}
+
+impl Generator {
+    pub fn new(frequency: Frequency, amplitude: ElectricPotential) -> Generator {
+        Generator { phase: 0.0, frequency, amplitude, }
+    }
+
+    pub fn frequency(&self) -> Frequency {
+        self.frequency
+    }
+
+    pub fn amplitude(&self) -> ElectricPotential {
+        self.amplitude
+    }
+
+    /// Nudges the amplitude by `delta`, clamping at zero so the loop
+    /// filter cannot drive the setpoint negative.
+    pub fn nudge_amplitude(&mut self, delta: ElectricPotential) {
+        self.amplitude = ElectricPotential::new::<volt>((self.amplitude + delta).get::<volt>().max(0.0));
+    }
+
+    /// Advances the phase accumulator by `dt` and returns the
+    /// instantaneous, zero-centered sample; the caller is responsible for
+    /// level-shifting and scaling it to a DAC code.
+    pub fn next_sample(&mut self, dt: Duration) -> ElectricPotential {
+        self.phase = (self.phase + self.frequency.get::<hertz>() * dt.as_secs_f64()).fract();
+        ElectricPotential::new::<volt>(self.amplitude.get::<volt>() * (2.0 * PI * self.phase).sin())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn samples_stay_within_the_commanded_amplitude() {
+        let mut generator = Generator::new(Frequency::new::<hertz>(60.0), ElectricPotential::new::<volt>(2.0));
+        let dt = Duration::from_micros(100);
+        for _ in 0..10_000 {
+            let sample = generator.next_sample(dt);
+            assert!(sample.get::<volt>().abs() <= 2.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn nudge_amplitude_clamps_at_zero() {
+        let mut generator = Generator::new(Frequency::new::<hertz>(60.0), ElectricPotential::new::<volt>(1.0));
+        generator.nudge_amplitude(ElectricPotential::new::<volt>(-5.0));
+        assert_eq!(generator.amplitude().get::<volt>(), 0.0);
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    net::SocketAddr,
     time::{
         Instant,
         Duration,
@@ -13,9 +14,17 @@ use structopt::{
     StructOpt,
 };
 
+use uom::si::{
+    f64::{
+        Frequency,
+        ElectricPotential,
+    },
+    frequency::hertz,
+    electric_potential::volt,
+};
+
 use rpi_lfa::{
     Volt,
-    Hertz,
     ac_driver,
 };
 
@@ -23,10 +32,31 @@ use rpi_lfa_rppal::{
     mcp3008,
 };
 
+mod net;
+mod cic_filter;
+mod mcp4922;
+mod generate;
+mod pll;
+mod deglitch;
+mod units;
+
 #[derive(Debug)]
 enum Error {
     ChannelIsNotInRangeFrom0To7 { provided: usize, },
     Mcp3008(mcp3008::Error),
+    Mcp4922(mcp4922::Error),
+    Net(net::Error),
+    DifferentialChannelIsNotEvenFrom0To6 { provided: usize, },
+    NoChannelProvided,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+enum Command {
+    /// continuously probe the mcp3008 and track the ac carrier (default)
+    Measure(CliArgs),
+    /// synthesize a sine carrier on the mcp4922 dac, optionally leveled against the measured carrier
+    Generate(CliGenerate),
 }
 
 #[derive(Clone, StructOpt, Debug)]
@@ -34,17 +64,32 @@ enum Error {
 struct CliArgs {
     #[structopt(flatten)]
     pub mcp3008: CliMcp3008,
+    #[structopt(flatten)]
+    pub filter: CliFilter,
+    #[structopt(flatten)]
+    pub track: CliTrack,
+    #[structopt(flatten)]
+    pub deglitch: CliDeglitch,
+    #[structopt(flatten)]
+    pub units: CliUnits,
     /// dump stats delay timeout (in milliseconds)
     #[structopt(long = "dump-stats-delay", short = "t", default_value = "1000")]
     dump_stats_delay: usize,
+    /// address to listen on for TCP clients streaming live samples (e.g. "0.0.0.0:8888")
+    #[structopt(long = "listen")]
+    listen: Option<SocketAddr>,
 }
 
 #[derive(Clone, StructOpt, Debug)]
 #[structopt(setting = AppSettings::DeriveDisplayOrder)]
 struct CliMcp3008 {
-    /// mcp3008 carrier channel
-    #[structopt(long = "mcp3008-carrier-channel", short = "c", default_value = "0")]
-    carrier_channel: usize,
+    /// mcp3008 carrier channel(s) to scan round-robin, comma separated (e.g. "0,1,2"); a pair's lower
+    /// channel number when --mcp3008-differential is set
+    #[structopt(long = "mcp3008-channels", short = "c", default_value = "0", use_delimiter = true)]
+    channels: Vec<usize>,
+    /// probe channels in differential mode, pairing each listed channel with the next one (CH0-CH1, CH2-CH3, ...)
+    #[structopt(long = "mcp3008-differential")]
+    differential: bool,
     /// mcp3008 voltage drain (vdd) in volts
     #[structopt(long = "mcp3008-voltage-drain", short = "v", possible_values = &CliMcp3008Vdd::variants(), case_insensitive = true)]
     voltage_drain: CliMcp3008Vdd,
@@ -61,55 +106,407 @@ arg_enum! {
     }
 }
 
-fn main() -> Result<(), Error> {
-    pretty_env_logger::init_timed();
-    let cli_args = CliArgs::from_args();
-    log::info!("program started as: {:?}", cli_args);
-
-    let mcp3008_channel = match cli_args.mcp3008.carrier_channel {
-        0 => mcp3008::Channel::Ch0,
-        1 => mcp3008::Channel::Ch1,
-        2 => mcp3008::Channel::Ch2,
-        3 => mcp3008::Channel::Ch3,
-        4 => mcp3008::Channel::Ch4,
-        5 => mcp3008::Channel::Ch5,
-        6 => mcp3008::Channel::Ch6,
-        7 => mcp3008::Channel::Ch7,
-        provided => return Err(Error::ChannelIsNotInRangeFrom0To7 { provided, }),
-    };
+arg_enum! {
+    #[derive(Clone, Debug)]
+    enum CliMcp4922Channel {
+        A,
+        B,
+    }
+}
 
-    let mcp3008_params = mcp3008::Params {
-        voltage_drain: match cli_args.mcp3008.voltage_drain {
+arg_enum! {
+    #[derive(Clone, Debug)]
+    enum CliVoltageUnit {
+        Volts,
+        Millivolts,
+    }
+}
+
+arg_enum! {
+    #[derive(Clone, Debug)]
+    enum CliFrequencyUnit {
+        Hertz,
+        Kilohertz,
+    }
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+struct CliUnits {
+    /// voltage unit used when formatting the stats dump
+    #[structopt(long = "units-voltage", default_value = "volts", possible_values = &CliVoltageUnit::variants(), case_insensitive = true)]
+    voltage: CliVoltageUnit,
+    /// frequency unit used when formatting the stats dump
+    #[structopt(long = "units-frequency", default_value = "hertz", possible_values = &CliFrequencyUnit::variants(), case_insensitive = true)]
+    frequency: CliFrequencyUnit,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+struct CliFilter {
+    /// order of the CIC/SINC decimation pre-filter (number of cascaded integrator/comb stages); 0 disables it
+    #[structopt(long = "filter-order", default_value = "0")]
+    filter_order: usize,
+    /// decimation factor for the CIC/SINC pre-filter
+    #[structopt(long = "decimation", default_value = "1")]
+    decimation: usize,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+struct CliTrack {
+    /// run a software loop tracking the carrier frequency; its tracked frequency is logged alongside
+    /// the block-averaged frequency, which is still computed and logged as well
+    #[structopt(long = "track")]
+    enabled: bool,
+    /// initial/reacquisition carrier frequency estimate (Hz) the tracking loop is seeded with; the
+    /// loop has no NCO and corrects this estimate from the measured period between zero crossings
+    #[structopt(long = "track-initial-frequency", default_value = "50.0")]
+    initial_frequency: f64,
+    /// PLL loop filter proportional gain
+    #[structopt(long = "track-kp", default_value = "0.1")]
+    kp: f64,
+    /// PLL loop filter integral gain
+    #[structopt(long = "track-ki", default_value = "0.01")]
+    ki: f64,
+    /// PLL integral clamp (accumulated frequency error times measured period, i.e. cycles) to prevent
+    /// windup when the carrier is briefly lost
+    #[structopt(long = "track-integral-clamp", default_value = "50.0")]
+    integral_clamp: f64,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+struct CliDeglitch {
+    /// stabilize the zero-crossing period estimate by emitting the median of all candidate edges in a sliding window, instead of the first detected edge
+    #[structopt(long = "deglitch")]
+    enabled: bool,
+    /// expected carrier frequency (Hz), used to size the deglitching sliding window to one period
+    #[structopt(long = "deglitch-expected-frequency", default_value = "50.0")]
+    expected_frequency: f64,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+#[structopt(setting = AppSettings::DeriveDisplayOrder)]
+struct CliGenerate {
+    #[structopt(flatten)]
+    pub mcp3008: CliMcp3008,
+    /// carrier frequency to synthesize (Hz)
+    #[structopt(long = "generate-frequency", short = "f")]
+    frequency: f64,
+    /// initial carrier amplitude to synthesize (volts, zero-to-peak)
+    #[structopt(long = "generate-amplitude", short = "a")]
+    amplitude: f64,
+    /// mcp4922 dac output channel
+    #[structopt(long = "mcp4922-channel", default_value = "a", possible_values = &CliMcp4922Channel::variants(), case_insensitive = true)]
+    dac_channel: CliMcp4922Channel,
+    /// mcp4922 voltage reference (volts), used to scale synthesized samples to dac codes
+    #[structopt(long = "mcp4922-voltage-reference")]
+    dac_voltage_ref: f64,
+    /// proportional gain nudging the generated amplitude so the measured carrier amplitude converges to
+    /// the commanded one
+    #[structopt(long = "generate-loop-gain", default_value = "0.1")]
+    loop_gain: f64,
+    #[structopt(flatten)]
+    pub units: CliUnits,
+    /// dump stats delay timeout (in milliseconds)
+    #[structopt(long = "dump-stats-delay", short = "t", default_value = "1000")]
+    dump_stats_delay: usize,
+}
+
+fn units_format_from(cli: &CliUnits) -> units::Format {
+    units::Format {
+        voltage: match cli.voltage {
+            CliVoltageUnit::Volts => units::VoltageUnit::Volts,
+            CliVoltageUnit::Millivolts => units::VoltageUnit::Millivolts,
+        },
+        frequency: match cli.frequency {
+            CliFrequencyUnit::Hertz => units::FrequencyUnit::Hertz,
+            CliFrequencyUnit::Kilohertz => units::FrequencyUnit::Kilohertz,
+        },
+    }
+}
+
+fn mcp3008_channel_from_index(index: usize) -> Result<mcp3008::Channel, Error> {
+    match index {
+        0 => Ok(mcp3008::Channel::Ch0),
+        1 => Ok(mcp3008::Channel::Ch1),
+        2 => Ok(mcp3008::Channel::Ch2),
+        3 => Ok(mcp3008::Channel::Ch3),
+        4 => Ok(mcp3008::Channel::Ch4),
+        5 => Ok(mcp3008::Channel::Ch5),
+        6 => Ok(mcp3008::Channel::Ch6),
+        7 => Ok(mcp3008::Channel::Ch7),
+        provided => Err(Error::ChannelIsNotInRangeFrom0To7 { provided, }),
+    }
+}
+
+/// `index` names the lower channel of an adjacent differential pair
+/// (CH0-CH1, CH2-CH3, CH4-CH5, CH6-CH7).
+fn mcp3008_differential_channel_from_index(index: usize) -> Result<mcp3008::Channel, Error> {
+    match index {
+        0 => Ok(mcp3008::Channel::Diff0Plus1Minus),
+        2 => Ok(mcp3008::Channel::Diff2Plus3Minus),
+        4 => Ok(mcp3008::Channel::Diff4Plus5Minus),
+        6 => Ok(mcp3008::Channel::Diff6Plus7Minus),
+        provided => Err(Error::DifferentialChannelIsNotEvenFrom0To6 { provided, }),
+    }
+}
+
+fn mcp3008_channels_from(cli: &CliMcp3008) -> Result<Vec<mcp3008::Channel>, Error> {
+    cli.channels.iter()
+        .map(|&index| if cli.differential {
+            mcp3008_differential_channel_from_index(index)
+        } else {
+            mcp3008_channel_from_index(index)
+        })
+        .collect()
+}
+
+fn mcp3008_params_from(cli: &CliMcp3008) -> mcp3008::Params {
+    mcp3008::Params {
+        voltage_drain: match cli.voltage_drain {
             CliMcp3008Vdd::Positive3v3 =>
                 mcp3008::Vdd::Positive3v3,
             CliMcp3008Vdd::Positive5v =>
                 mcp3008::Vdd::Positive5v,
         },
-        voltage_ref: match cli_args.mcp3008.voltage_ref {
+        voltage_ref: match cli.voltage_ref {
             None =>
                 mcp3008::Vref::EqualToVdd,
             Some(value) =>
                 mcp3008::Vref::Other {
-                    voltage: Volt(value),
+                    voltage: units::rpi_from_potential(units::potential_from_cli(value)),
                 },
         },
+    }
+}
+
+/// Drives the mcp3008 state machine one step and returns the voltage
+/// read for `channel`, if a conversion for that channel just completed.
+fn poll_mcp3008(
+    session: mcp3008::Session,
+    channel: mcp3008::Channel,
+)
+    -> Result<(mcp3008::Session, Option<Volt>), Error>
+{
+    let mut voltage_read = None;
+
+    let session = match session {
+
+        mcp3008::Session::Initializing(initializing) =>
+            match initializing.probe().map_err(Error::Mcp3008)? {
+                mcp3008::InitializingOp::Idle(initializing) =>
+                    initializing.into(),
+                mcp3008::InitializingOp::Ready(ready) => {
+                    log::debug!("mcp3008 ready");
+                    ready.into()
+                },
+            },
+
+        mcp3008::Session::Ready(ready) =>
+            ready.probe_channel(channel).into(),
+
+        mcp3008::Session::Probing(probing) =>
+            match probing.poll().map_err(Error::Mcp3008)? {
+                mcp3008::ProbingOp::Idle(probing) =>
+                    probing.into(),
+                mcp3008::ProbingOp::Done { channel: done_channel, value, ready, } if done_channel == channel => {
+                    voltage_read = Some(value);
+                    ready.into()
+                },
+                mcp3008::ProbingOp::Done { ready, .. } =>
+                    ready.into(),
+            },
+
     };
 
+    Ok((session, voltage_read))
+}
+
+/// Drives the ac_driver state machine one step and returns the latest
+/// estimated values, if any were produced for this voltage read, along
+/// with whether this step transitioned through CarrierLost.
+fn poll_ac_driver(
+    session: ac_driver::Session,
+    now: Instant,
+    voltage: Volt,
+)
+    -> (ac_driver::Session, Option<ac_driver::Values>, bool)
+{
+    let mut values_read = None;
+    let mut carrier_lost = false;
+
+    let session = match session {
+
+        ac_driver::Session::Initializing(initializing) =>
+            match initializing.voltage_read(now, voltage) {
+                ac_driver::InitializingOp::Idle(initializing) =>
+                    initializing.into(),
+                ac_driver::InitializingOp::CarrierDetected(estimated) => {
+                    log::debug!("ac_driver carrier detected");
+                    values_read = Some(estimated.values().clone());
+                    estimated.into()
+                },
+            },
+
+        ac_driver::Session::Estimated(estimated) =>
+            match estimated.voltage_read(now, voltage) {
+                ac_driver::EstimatedOp::Idle(estimated) => {
+                    values_read = Some(estimated.values().clone());
+                    estimated.into()
+                },
+                ac_driver::EstimatedOp::CarrierLost(initializing) => {
+                    log::debug!("ac_driver carrier lost");
+                    carrier_lost = true;
+                    initializing.into()
+                },
+            },
+
+    };
+
+    (session, values_read, carrier_lost)
+}
+
+/// Per-channel acquisition state for the round-robin multi-channel scan:
+/// each scanned mcp3008 channel gets its own decimation filter, its own
+/// `ac_driver` session, and its own running stats.
+struct ChannelState {
+    channel: mcp3008::Channel,
+    cic_filter: Option<cic_filter::Filter>,
+    ac_driver_session: ac_driver::Session,
+    ac_samples: usize,
+    ac_avg_hz: Frequency,
+    ac_avg_hi: ElectricPotential,
+    ac_avg_lo: ElectricPotential,
+    pll: Option<pll::Pll>,
+    pll_last_tick: Option<Instant>,
+    pll_tracked_hz: Option<Frequency>,
+    deglitcher: Option<deglitch::Deglitcher>,
+    deglitch_hz: Option<Frequency>,
+}
+
+impl ChannelState {
+    fn new(
+        channel: mcp3008::Channel,
+        cic_filter: Option<cic_filter::Filter>,
+        pll: Option<pll::Pll>,
+        deglitcher: Option<deglitch::Deglitcher>,
+    )
+        -> ChannelState
+    {
+        ChannelState {
+            channel,
+            cic_filter,
+            ac_driver_session: ac_driver::Session::new(),
+            ac_samples: 0,
+            ac_avg_hz: Frequency::new::<hertz>(0.0),
+            ac_avg_hi: ElectricPotential::new::<volt>(0.0),
+            ac_avg_lo: ElectricPotential::new::<volt>(0.0),
+            pll,
+            pll_last_tick: None,
+            pll_tracked_hz: None,
+            deglitcher,
+            deglitch_hz: None,
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    pretty_env_logger::init_timed();
+    let command = command_from_args();
+    log::info!("program started as: {:?}", command);
+
+    match command {
+        Command::Measure(cli_args) => run_measure(cli_args),
+        Command::Generate(cli_generate) => run_generate(cli_generate),
+    }
+}
+
+/// `Command` has no bare (no-subcommand) invocation of its own, but the
+/// old single-mode CLI ran with no subcommand token at all; insert the
+/// implicit `measure` token so that invocation still works. Rather than
+/// hand-matching a list of known subcommand names (which silently breaks
+/// for any future subcommand's own flags), this tries the real parse
+/// first and only falls back to the implicit token when that genuinely
+/// fails, so it stays correct as subcommands are added without needing
+/// to be touched.
+fn command_from_args() -> Command {
+    let args: Vec<String> = std::env::args().collect();
+
+    match Command::from_iter_safe(&args) {
+        Ok(command) =>
+            return command,
+        Err(error) if error.kind == structopt::clap::ErrorKind::HelpDisplayed
+            || error.kind == structopt::clap::ErrorKind::VersionDisplayed =>
+            error.exit(),
+        Err(_) => {},
+    }
+
+    let mut retry_args = args.clone();
+    retry_args.insert(1, "measure".to_string());
+    if let Ok(command) = Command::from_iter_safe(&retry_args) {
+        return command;
+    }
+
+    // neither attempt parsed: report the original error/usage, not one
+    // confused by our own inserted token
+    Command::from_args()
+}
+
+fn run_measure(cli_args: CliArgs) -> Result<(), Error> {
+    let mcp3008_channels = mcp3008_channels_from(&cli_args.mcp3008)?;
+    let mcp3008_params = mcp3008_params_from(&cli_args.mcp3008);
+
     let dump_delay = Duration::from_millis(cli_args.dump_stats_delay as u64);
 
+    let mut channel_states: Vec<ChannelState> = mcp3008_channels.into_iter()
+        .map(|channel| {
+            let cic_filter = if cli_args.filter.filter_order > 0 {
+                Some(cic_filter::Filter::new(&cic_filter::Params {
+                    order: cli_args.filter.filter_order,
+                    decimation: cli_args.filter.decimation.max(1),
+                }))
+            } else {
+                None
+            };
+            let pll = if cli_args.track.enabled {
+                Some(pll::Pll::new(
+                    pll::Params {
+                        kp: cli_args.track.kp,
+                        ki: cli_args.track.ki,
+                        integral_clamp: cli_args.track.integral_clamp,
+                    },
+                    units::frequency_from_cli(cli_args.track.initial_frequency),
+                ))
+            } else {
+                None
+            };
+            let deglitcher = if cli_args.deglitch.enabled {
+                Some(deglitch::Deglitcher::new(Duration::from_secs_f64(1.0 / cli_args.deglitch.expected_frequency)))
+            } else {
+                None
+            };
+            ChannelState::new(channel, cic_filter, pll, deglitcher)
+        })
+        .collect();
+    let mut next_channel = 0;
     let mut ac_last_dump = Instant::now();
-    let mut ac_samples = 0;
-    let mut ac_avg_hz = 0.0;
-    let mut ac_avg_hi = 0.0;
-    let mut ac_avg_lo = 0.0;
+    let units_format = units_format_from(&cli_args.units);
 
-    let mut ac_driver_session = ac_driver::Session::new();
     let mut mcp3008_session = mcp3008::Session::new(&mcp3008_params)
         .map_err(Error::Mcp3008)?;
 
-    loop {
-        let mut channel_voltage_read = None;
+    let mut net_server = match cli_args.listen {
+        None =>
+            None,
+        Some(addr) => {
+            log::info!("net: listening on {}", addr);
+            Some(net::Server::bind(addr).map_err(Error::Net)?)
+        },
+    };
 
+    loop {
         match mcp3008_session {
 
             mcp3008::Session::Initializing(initializing) =>
@@ -123,14 +520,101 @@ fn main() -> Result<(), Error> {
                 },
 
             mcp3008::Session::Ready(ready) =>
-                mcp3008_session = ready.probe_channel(mcp3008_channel).into(),
+                mcp3008_session = ready.probe_channel(channel_states[next_channel].channel).into(),
 
             mcp3008::Session::Probing(probing) =>
                 match probing.poll().map_err(Error::Mcp3008)? {
                     mcp3008::ProbingOp::Idle(probing) =>
                         mcp3008_session = probing.into(),
-                    mcp3008::ProbingOp::Done { channel, value, ready, } if channel == mcp3008_channel => {
-                        channel_voltage_read = Some(value);
+                    mcp3008::ProbingOp::Done { channel, value, ready, } if channel == channel_states[next_channel].channel => {
+                        let now = Instant::now();
+                        let channel_index = next_channel;
+                        let state = &mut channel_states[channel_index];
+
+                        let filtered_voltage_read = match state.cic_filter.as_mut() {
+                            Some(filter) => filter.add_sample(units::potential_from_rpi(value)),
+                            None => Some(units::potential_from_rpi(value)),
+                        };
+
+                        let mut ac_values_read = None;
+
+                        if let Some(voltage) = filtered_voltage_read {
+                            let carrier_lost;
+                            (state.ac_driver_session, ac_values_read, carrier_lost) = poll_ac_driver(state.ac_driver_session, now, units::rpi_from_potential(voltage));
+
+                            if let Some(pll) = state.pll.as_mut() {
+                                if carrier_lost {
+                                    pll.reset(units::frequency_from_cli(cli_args.track.initial_frequency));
+                                    state.pll_last_tick = None;
+                                    state.pll_tracked_hz = None;
+                                } else {
+                                    let dt = match state.pll_last_tick {
+                                        Some(last_tick) => now.duration_since(last_tick),
+                                        None => Duration::from_secs(0),
+                                    };
+                                    state.pll_last_tick = Some(now);
+                                    state.pll_tracked_hz = Some(pll.advance(dt, voltage));
+                                }
+                            }
+
+                            if let Some(deglitcher) = state.deglitcher.as_mut() {
+                                if let Some(hz) = deglitcher.add_sample(now, voltage) {
+                                    state.deglitch_hz = Some(hz);
+                                }
+                            }
+
+                            if let Some(server) = net_server.as_mut() {
+                                server.publish(channel_index, value, ac_values_read.as_ref());
+                            }
+
+                            if let Some(ac_values) = ac_values_read {
+                                state.ac_samples += 1;
+                                state.ac_avg_hz += units::frequency_from_rpi(ac_values.frequency);
+                                state.ac_avg_hi += units::potential_from_rpi(ac_values.amplitude.max.value);
+                                state.ac_avg_lo += units::potential_from_rpi(ac_values.amplitude.min.value);
+                            }
+
+                            if now.duration_since(ac_last_dump) >= dump_delay {
+                                for (index, state) in channel_states.iter_mut().enumerate() {
+                                    if state.ac_samples == 0 && state.pll_tracked_hz.is_none() && state.deglitch_hz.is_none() {
+                                        log::info!("channel {}: no samples collected yet", index);
+                                    } else {
+                                        if let Some(tracked_hz) = state.pll_tracked_hz {
+                                            log::info!(
+                                                "channel {} - tracked frequency: {}",
+                                                index, units_format.format_frequency(tracked_hz),
+                                            );
+                                        }
+                                        if let Some(deglitch_hz) = state.deglitch_hz {
+                                            log::info!(
+                                                "channel {} - deglitched frequency: {}",
+                                                index, units_format.format_frequency(deglitch_hz),
+                                            );
+                                        }
+                                        if state.ac_samples > 0 {
+                                            log::info!(
+                                                "channel {} - avg frequency: {}, avg amplitude hi: {}, avg amplitude lo: {}",
+                                                index,
+                                                units_format.format_frequency(state.ac_avg_hz / state.ac_samples as f64),
+                                                units_format.format_voltage(state.ac_avg_hi / state.ac_samples as f64),
+                                                units_format.format_voltage(state.ac_avg_lo / state.ac_samples as f64),
+                                            );
+                                        }
+                                    }
+                                    state.ac_samples = 0;
+                                    state.ac_avg_hz = Frequency::new::<hertz>(0.0);
+                                    state.ac_avg_hi = ElectricPotential::new::<volt>(0.0);
+                                    state.ac_avg_lo = ElectricPotential::new::<volt>(0.0);
+                                }
+                                ac_last_dump = now;
+                            }
+                        } else if let Some(server) = net_server.as_mut() {
+                            // filter pipeline is still warming up / mid-decimation for this
+                            // probe; publish the raw per-probe read anyway, per chunk0-1's spec
+                            server.publish(channel_index, value, None);
+                        }
+
+                        next_channel = (next_channel + 1) % channel_states.len();
                         mcp3008_session = ready.into();
                     },
                     mcp3008::ProbingOp::Done { ready, .. } =>
@@ -138,58 +622,79 @@ fn main() -> Result<(), Error> {
                 },
 
         }
+    }
+}
 
-        if let Some(voltage) = channel_voltage_read {
-            let mut ac_values_read = None;
-            let now = Instant::now();
-            match ac_driver_session {
-
-                ac_driver::Session::Initializing(initializing) =>
-                    match initializing.voltage_read(now, voltage) {
-                        ac_driver::InitializingOp::Idle(initializing) =>
-                            ac_driver_session = initializing.into(),
-                        ac_driver::InitializingOp::CarrierDetected(estimated) => {
-                            log::debug!("ac_driver carrier detected");
-                            ac_values_read = Some(estimated.values().clone());
-                            ac_driver_session = estimated.into();
-                        },
-                    },
+fn run_generate(cli_generate: CliGenerate) -> Result<(), Error> {
+    let mcp3008_channel = mcp3008_channels_from(&cli_generate.mcp3008)?
+        .into_iter()
+        .next()
+        .ok_or(Error::NoChannelProvided)?;
+    let mcp3008_params = mcp3008_params_from(&cli_generate.mcp3008);
 
-                ac_driver::Session::Estimated(estimated) =>
-                    match estimated.voltage_read(now, voltage) {
-                        ac_driver::EstimatedOp::Idle(estimated) => {
-                            ac_values_read = Some(estimated.values().clone());
-                            ac_driver_session = estimated.into();
-                        },
-                        ac_driver::EstimatedOp::CarrierLost(initializing) => {
-                            log::debug!("ac_driver carrier lost");
-                            ac_driver_session = initializing.into();
-                        },
-                    },
+    let dac_params = mcp4922::Params {
+        channel: match cli_generate.dac_channel {
+            CliMcp4922Channel::A => mcp4922::Channel::A,
+            CliMcp4922Channel::B => mcp4922::Channel::B,
+        },
+    };
+    let mut dac = mcp4922::Dac::new(&dac_params)
+        .map_err(Error::Mcp4922)?;
+    let dac_vref = units::potential_from_cli(cli_generate.dac_voltage_ref);
 
-            }
+    let target_amplitude = units::potential_from_cli(cli_generate.amplitude);
+
+    let mut generator = generate::Generator::new(
+        units::frequency_from_cli(cli_generate.frequency),
+        units::potential_from_cli(cli_generate.amplitude),
+    );
+
+    let mut mcp3008_session = mcp3008::Session::new(&mcp3008_params)
+        .map_err(Error::Mcp3008)?;
+    let mut ac_driver_session = ac_driver::Session::new();
+
+    let dump_delay = Duration::from_millis(cli_generate.dump_stats_delay as u64);
+    let mut ac_last_dump = Instant::now();
+    let mut last_tick = Instant::now();
+    let units_format = units_format_from(&cli_generate.units);
+
+    loop {
+        let now = Instant::now();
+        let dt = now.duration_since(last_tick);
+        last_tick = now;
+
+        let sample = generator.next_sample(dt);
+        let dac_voltage = sample + dac_vref / 2.0;
+        let dac_code = mcp4922::code_for_voltage(dac_voltage, dac_vref);
+        dac.set(dac_code)
+            .map_err(Error::Mcp4922)?;
+
+        let channel_voltage_read;
+        (mcp3008_session, channel_voltage_read) = poll_mcp3008(mcp3008_session, mcp3008_channel)?;
+
+        if let Some(voltage) = channel_voltage_read {
+            let ac_values_read;
+            let _carrier_lost;
+            (ac_driver_session, ac_values_read, _carrier_lost) = poll_ac_driver(ac_driver_session, now, voltage);
 
             if let Some(ac_values) = ac_values_read {
-                ac_samples += 1;
-                ac_avg_hz += ac_values.frequency.0;
-                ac_avg_hi += ac_values.amplitude.max.value.0;
-                ac_avg_lo += ac_values.amplitude.min.value.0;
+                let measured_amplitude = (units::potential_from_rpi(ac_values.amplitude.max.value)
+                    - units::potential_from_rpi(ac_values.amplitude.min.value)) / 2.0;
+                // drive off the originally commanded target, not the generator's own
+                // (already-nudged) amplitude, or this becomes positive feedback whenever
+                // the measured amplitude differs from the commanded one by a fixed factor
+                let error = target_amplitude - measured_amplitude;
+                generator.nudge_amplitude(error * cli_generate.loop_gain);
             }
+        }
 
-            if now.duration_since(ac_last_dump) >= dump_delay {
-                if ac_samples == 0 {
-                    log::info!("no samples collected yet");
-                } else {
-                    log::info!(" - avg frequency: {:?}", Hertz(ac_avg_hz / ac_samples as f64));
-                    log::info!(" - avg amplitude hi: {:?}", Volt(ac_avg_hi / ac_samples as f64));
-                    log::info!(" - avg amplitude lo: {:?}", Volt(ac_avg_lo / ac_samples as f64));
-                }
-                ac_samples = 0;
-                ac_avg_hz = 0.0;
-                ac_avg_hi = 0.0;
-                ac_avg_lo = 0.0;
-                ac_last_dump = now;
-            }
+        if now.duration_since(ac_last_dump) >= dump_delay {
+            log::info!(
+                " - generating frequency: {}, amplitude: {}",
+                units_format.format_frequency(generator.frequency()),
+                units_format.format_voltage(generator.amplitude()),
+            );
+            ac_last_dump = now;
         }
     }
 }
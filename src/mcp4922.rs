@@ -0,0 +1,84 @@
+use rppal::spi::{
+    Bus,
+    Mode,
+    SlaveSelect,
+    Spi,
+};
+
+use uom::si::{
+    f64::ElectricPotential,
+    electric_potential::volt,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Spi(rppal::spi::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+#[derive(Debug)]
+pub struct Params {
+    pub channel: Channel,
+}
+
+/// Driver for the MCP4921/MCP4922 SPI DACs, analogous in spirit to the
+/// `mcp3008` ADC driver but synchronous: a single SPI transfer both
+/// selects the channel and latches the output, so there is no
+/// initializing/probing state machine to drive.
+pub struct Dac {
+    spi: Spi,
+    channel: Channel,
+}
+
+impl Dac {
+    pub fn new(params: &Params) -> Result<Dac, Error> {
+        let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 1_000_000, Mode::Mode0)
+            .map_err(Error::Spi)?;
+        Ok(Dac { spi, channel: params.channel, })
+    }
+
+    /// Clamps `code` to the 12-bit DAC range and writes the command word
+    /// to the device: config nibble (channel select, unbuffered, gain
+    /// x1, active) followed by the 12 data bits, MSB first.
+    pub fn set(&mut self, code: u16) -> Result<(), Error> {
+        let data = code.min(0x0FFF);
+        let config: u16 = match self.channel {
+            Channel::A => 0b0011_0000_0000_0000,
+            Channel::B => 0b1011_0000_0000_0000,
+        };
+        let command = config | data;
+        let bytes = [(command >> 8) as u8, (command & 0xFF) as u8];
+        self.spi.write(&bytes)
+            .map_err(Error::Spi)?;
+        Ok(())
+    }
+}
+
+/// Maps a voltage in `[0, vref]` to a 12-bit DAC code, clamping out of
+/// range values to the nearest rail.
+pub fn code_for_voltage(voltage: ElectricPotential, vref: ElectricPotential) -> u16 {
+    let ratio = (voltage.get::<volt>() / vref.get::<volt>()).max(0.0).min(1.0);
+    (ratio * 4095.0).round() as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mid_scale_voltage_maps_to_half_code() {
+        let code = code_for_voltage(ElectricPotential::new::<volt>(2.5), ElectricPotential::new::<volt>(5.0));
+        assert_eq!(code, 2048);
+    }
+
+    #[test]
+    fn out_of_range_voltages_clamp_to_the_rails() {
+        assert_eq!(code_for_voltage(ElectricPotential::new::<volt>(-1.0), ElectricPotential::new::<volt>(5.0)), 0);
+        assert_eq!(code_for_voltage(ElectricPotential::new::<volt>(10.0), ElectricPotential::new::<volt>(5.0)), 4095);
+    }
+}
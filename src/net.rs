@@ -0,0 +1,138 @@
+use std::{
+    io::{
+        Write,
+        ErrorKind,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+        SocketAddr,
+    },
+    time::Instant,
+};
+
+use rpi_lfa::{
+    Volt,
+    ac_driver,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Bind(std::io::Error),
+    SetNonblocking(std::io::Error),
+}
+
+/// Once a client's unflushed backlog exceeds this many bytes it is
+/// dropped outright rather than left to grow without bound: a client
+/// that never reads would otherwise accumulate queued records for the
+/// lifetime of the acquisition.
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// A connected client together with whatever tail of a previous record
+/// did not fit in its socket buffer; kept so the next `publish()` call
+/// resumes the write instead of starting a fresh record mid-line.
+struct Client {
+    stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl Client {
+    /// Writes as much of `pending` as the socket will currently accept.
+    /// Returns `false` once the client should be dropped.
+    fn flush_pending(&mut self) -> bool {
+        if self.pending.len() > MAX_PENDING_BYTES {
+            log::warn!("net: dropping client, unflushed backlog exceeded {} bytes", MAX_PENDING_BYTES);
+            return false;
+        }
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) =>
+                    return false,
+                Ok(written) => {
+                    self.pending.drain(..written);
+                },
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock =>
+                    // client is not keeping up; leave the remainder queued
+                    // for the next publish rather than dropping it
+                    return true,
+                Err(error) => {
+                    log::info!("net: client disconnected: {:?}", error);
+                    return false;
+                },
+            }
+        }
+        true
+    }
+}
+
+/// Publishes a record line for every probe to all currently connected
+/// TCP clients. Never blocks the acquisition loop: a client that is not
+/// keeping up has its unsent bytes queued rather than the loop waiting
+/// on it, and each new record is appended behind any queued bytes so a
+/// slow client receives whole records in order, never one torn mid-line.
+pub struct Server {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    started_at: Instant,
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr) -> Result<Server, Error> {
+        let listener = TcpListener::bind(addr)
+            .map_err(Error::Bind)?;
+        listener.set_nonblocking(true)
+            .map_err(Error::SetNonblocking)?;
+        Ok(Server { listener, clients: Vec::new(), started_at: Instant::now(), })
+    }
+
+    fn accept_pending(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    if let Err(error) = stream.set_nonblocking(true) {
+                        log::warn!("net: dropping client {}, set_nonblocking failed: {:?}", peer_addr, error);
+                        continue;
+                    }
+                    log::info!("net: client connected from {}", peer_addr);
+                    self.clients.push(Client { stream, pending: Vec::new(), });
+                },
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock =>
+                    break,
+                Err(error) => {
+                    log::warn!("net: accept error: {:?}", error);
+                    break;
+                },
+            }
+        }
+    }
+
+    /// Buffers and opportunistically writes a newline-delimited record
+    /// for this probe. Timestamp is microseconds since the server started.
+    pub fn publish(&mut self, channel: usize, voltage: Volt, ac_values: Option<&ac_driver::Values>) {
+        self.accept_pending();
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let micros = self.started_at.elapsed().as_micros();
+        let record = match ac_values {
+            None =>
+                format!("{} {} {:?}\n", micros, channel, voltage.0),
+            Some(values) =>
+                format!(
+                    "{} {} {:?} {:?} {:?} {:?}\n",
+                    micros,
+                    channel,
+                    voltage.0,
+                    values.frequency.0,
+                    values.amplitude.max.value.0,
+                    values.amplitude.min.value.0,
+                ),
+        };
+
+        self.clients.retain_mut(|client| {
+            client.pending.extend_from_slice(record.as_bytes());
+            client.flush_pending()
+        });
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use uom::si::{
+    f64::{
+        Frequency,
+        ElectricPotential,
+    },
+    frequency::hertz,
+    electric_potential::volt,
+};
+
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub kp: f64,
+    pub ki: f64,
+    pub integral_clamp: f64,
+}
+
+/// A software frequency-locked loop that tracks the carrier frequency
+/// continuously instead of averaging over a block of samples. The
+/// discriminator is the real elapsed time between consecutive
+/// negative-to-positive zero crossings of the input, compared against
+/// the currently tracked period: that error is zero-mean exactly when
+/// the estimate matches the true frequency. An earlier version compared
+/// an NCO's own running phase at each crossing instead, but for a
+/// sustained frequency offset that phase error is not zero-mean across a
+/// beat cycle, so the loop just dithered around its seed frequency and
+/// never converged.
+pub struct Pll {
+    params: Params,
+    frequency_hz: f64,
+    integral: f64,
+    time_since_crossing: f64,
+    last_sign: Option<bool>,
+    seen_crossing: bool,
+}
+
+impl Pll {
+    pub fn new(params: Params, initial_frequency: Frequency) -> Pll {
+        Pll {
+            params,
+            frequency_hz: initial_frequency.get::<hertz>(),
+            integral: 0.0,
+            time_since_crossing: 0.0,
+            last_sign: None,
+            seen_crossing: false,
+        }
+    }
+
+    /// Clears the accumulated integral term and the elapsed-since-crossing
+    /// clock, and reseeds the frequency estimate; call this on an
+    /// `ac_driver` CarrierLost transition so a stale lock does not bias
+    /// reacquisition.
+    pub fn reset(&mut self, frequency: Frequency) {
+        self.frequency_hz = frequency.get::<hertz>();
+        self.integral = 0.0;
+        self.time_since_crossing = 0.0;
+        self.last_sign = None;
+        self.seen_crossing = false;
+    }
+
+    /// Advances the loop by `dt` and folds in one voltage sample,
+    /// returning the current tracked frequency. Every negative-to-positive
+    /// crossing of `voltage` measures the real period since the previous
+    /// one and corrects the estimate by how far that measured period
+    /// disagrees with it.
+    pub fn advance(&mut self, dt: Duration, voltage: ElectricPotential) -> Frequency {
+        self.time_since_crossing += dt.as_secs_f64();
+
+        let sign = voltage.get::<volt>() >= 0.0;
+        if let Some(last_sign) = self.last_sign {
+            if !last_sign && sign {
+                let measured_period = self.time_since_crossing;
+                // the first crossing after new/reset has no previous one to
+                // measure a period from
+                if self.seen_crossing && measured_period > 0.0 {
+                    let measured_hz = 1.0 / measured_period;
+                    let error = measured_hz - self.frequency_hz;
+                    self.integral = (self.integral + error * measured_period)
+                        .max(-self.params.integral_clamp)
+                        .min(self.params.integral_clamp);
+                    let correction = self.params.kp * error + self.params.ki * self.integral;
+                    self.frequency_hz += correction;
+                }
+                self.time_since_crossing = 0.0;
+                self.seen_crossing = true;
+            }
+        }
+        self.last_sign = Some(sign);
+
+        Frequency::new::<hertz>(self.frequency_hz)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        f64::consts::PI,
+        time::Duration,
+    };
+
+    use uom::si::{
+        f64::{
+            Frequency,
+            ElectricPotential,
+        },
+        frequency::hertz,
+        electric_potential::volt,
+    };
+
+    use super::{Params, Pll};
+
+    fn run_sine(pll: &mut Pll, true_hz: f64, ticks: usize, dt: Duration) -> Frequency {
+        let mut phase = 0.0;
+        let mut tracked = Frequency::new::<hertz>(0.0);
+        for _ in 0..ticks {
+            phase = (phase + true_hz * dt.as_secs_f64()).fract();
+            let voltage = ElectricPotential::new::<volt>((2.0 * PI * phase).sin());
+            tracked = pll.advance(dt, voltage);
+        }
+        tracked
+    }
+
+    #[test]
+    fn converges_toward_a_higher_true_frequency() {
+        let mut pll = Pll::new(
+            Params { kp: 0.5, ki: 0.1, integral_clamp: 50.0, },
+            Frequency::new::<hertz>(50.0),
+        );
+        let dt = Duration::from_micros(100);
+        let tracked = run_sine(&mut pll, 60.0, 200_000, dt);
+        assert!(
+            (tracked.get::<hertz>() - 60.0).abs() < 1.0,
+            "expected convergence near 60 Hz, got {} Hz", tracked.get::<hertz>(),
+        );
+    }
+
+    #[test]
+    fn converges_toward_a_lower_true_frequency() {
+        let mut pll = Pll::new(
+            Params { kp: 0.5, ki: 0.1, integral_clamp: 50.0, },
+            Frequency::new::<hertz>(50.0),
+        );
+        let dt = Duration::from_micros(100);
+        let tracked = run_sine(&mut pll, 49.0, 200_000, dt);
+        assert!(
+            (tracked.get::<hertz>() - 49.0).abs() < 1.0,
+            "expected convergence near 49 Hz, got {} Hz", tracked.get::<hertz>(),
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use uom::si::{
+    f64::{
+        ElectricPotential,
+        Frequency,
+    },
+    electric_potential::{
+        volt,
+        millivolt,
+    },
+    frequency::{
+        hertz,
+        kilohertz,
+    },
+};
+
+use rpi_lfa::{
+    Volt as RpiVolt,
+    Hertz as RpiHertz,
+};
+
+/// Lifts a raw `rpi_lfa::Volt` reading to a dimensionally-checked
+/// `uom` quantity, so the filter/PLL/deglitch/generate math downstream
+/// can't accidentally mix volts with some other unit.
+pub fn potential_from_rpi(value: RpiVolt) -> ElectricPotential {
+    ElectricPotential::new::<volt>(value.0)
+}
+
+/// Lowers a `uom` electric potential back to the plain `rpi_lfa::Volt`
+/// newtype expected at the mcp3008/ac_driver API boundary (fixed by
+/// that external crate, so it cannot itself be made `uom`-aware).
+pub fn rpi_from_potential(value: ElectricPotential) -> RpiVolt {
+    RpiVolt(value.get::<volt>())
+}
+
+pub fn frequency_from_rpi(value: RpiHertz) -> Frequency {
+    Frequency::new::<hertz>(value.0)
+}
+
+pub fn rpi_from_frequency(value: Frequency) -> RpiHertz {
+    RpiHertz(value.get::<hertz>())
+}
+
+/// Tags a raw CLI-parsed value as volts; this is a type-level lift, not
+/// a validation (there is no invalid float value to reject), but it
+/// stops that value from being handed to downstream arithmetic unlabeled.
+pub fn potential_from_cli(value: f64) -> ElectricPotential {
+    ElectricPotential::new::<volt>(value)
+}
+
+/// Tags a raw CLI-parsed value as hertz; same caveat as
+/// `potential_from_cli`.
+pub fn frequency_from_cli(value: f64) -> Frequency {
+    Frequency::new::<hertz>(value)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum VoltageUnit {
+    Volts,
+    Millivolts,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FrequencyUnit {
+    Hertz,
+    Kilohertz,
+}
+
+/// Unit-aware formatting for the stats dump, selected by `--units-voltage`
+/// / `--units-frequency`; leans on uom's unit-tagged `get::<U>()` so the
+/// scaling is always correct rather than a manually maintained factor.
+#[derive(Clone, Copy, Debug)]
+pub struct Format {
+    pub voltage: VoltageUnit,
+    pub frequency: FrequencyUnit,
+}
+
+impl Format {
+    pub fn format_voltage(&self, value: ElectricPotential) -> String {
+        match self.voltage {
+            VoltageUnit::Volts => format!("{:.6} V", value.get::<volt>()),
+            VoltageUnit::Millivolts => format!("{:.3} mV", value.get::<millivolt>()),
+        }
+    }
+
+    pub fn format_frequency(&self, value: Frequency) -> String {
+        match self.frequency {
+            FrequencyUnit::Hertz => format!("{:.6} Hz", value.get::<hertz>()),
+            FrequencyUnit::Kilohertz => format!("{:.6} kHz", value.get::<kilohertz>()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rpi_potential_round_trips() {
+        let rpi = RpiVolt(3.3);
+        assert_eq!(rpi_from_potential(potential_from_rpi(rpi)).0, 3.3);
+    }
+
+    #[test]
+    fn rpi_frequency_round_trips() {
+        let rpi = RpiHertz(50.0);
+        assert_eq!(rpi_from_frequency(frequency_from_rpi(rpi)).0, 50.0);
+    }
+
+    #[test]
+    fn formats_millivolts() {
+        let format = Format { voltage: VoltageUnit::Millivolts, frequency: FrequencyUnit::Hertz, };
+        assert_eq!(format.format_voltage(ElectricPotential::new::<volt>(1.5)), "1500.000 mV");
+    }
+
+    #[test]
+    fn formats_kilohertz() {
+        let format = Format { voltage: VoltageUnit::Volts, frequency: FrequencyUnit::Kilohertz, };
+        assert_eq!(format.format_frequency(Frequency::new::<hertz>(2500.0)), "2.500000 kHz");
+    }
+}